@@ -1,8 +1,35 @@
 use im::Vector;
 use std::clone::Clone;
+use std::io::Cursor;
 use std::iter::FromIterator;
 use wasm_bindgen::prelude::*;
 
+mod wal;
+use wal::{EditLog, EditPayload};
+
+/// Every cell that differs between `old` and `new`, as `EditPayload`s ready
+/// to hand to `EditLog::grow`. Used by multi-cell operations like `fill`
+/// that can't cheaply compute their own diff the way `brush`'s single-cell
+/// edit can.
+fn diff_payloads(old: &Image, new: &Image) -> Vec<EditPayload> {
+    old.cells
+        .iter()
+        .zip(new.cells.iter())
+        .enumerate()
+        .filter_map(|(index, (&old_cell, &new_cell))| {
+            if old_cell == new_cell {
+                None
+            } else {
+                Some(EditPayload {
+                    index,
+                    old: old_cell,
+                    new: new_cell,
+                })
+            }
+        })
+        .collect()
+}
+
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
 //
@@ -11,11 +38,63 @@ use wasm_bindgen::prelude::*;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 struct Rgb {
     r: u8,
     g: u8,
     b: u8,
+    a: u8,
+}
+
+/// Source-over alpha compositing of `src` atop `dst`. Color channels blend
+/// with `src`'s alpha normalized from 0..255 to 0.0..1.0; the result's alpha
+/// follows the Porter-Duff "over" rule (`src_a + dst_a * (1 - src_a)`) rather
+/// than the same per-channel blend, so painting a translucent color over an
+/// opaque pixel can never make it less opaque.
+fn composite(src: Rgb, dst: Rgb) -> Rgb {
+    let sa = src.a as f32 / 255.0;
+    let da = dst.a as f32 / 255.0;
+    let blend = |s: u8, d: u8| -> u8 { (s as f32 * sa + d as f32 * (1.0 - sa)).round() as u8 };
+    let out_a = sa + da * (1.0 - sa);
+    Rgb {
+        r: blend(src.r, dst.r),
+        g: blend(src.g, dst.g),
+        b: blend(src.b, dst.b),
+        a: (out_a * 255.0).round() as u8,
+    }
+}
+
+#[cfg(test)]
+mod composite_tests {
+    use super::*;
+
+    #[test]
+    fn translucent_over_opaque_stays_opaque() {
+        let src = Rgb { r: 0, g: 0, b: 0, a: 128 };
+        let dst = Rgb { r: 255, g: 255, b: 255, a: 255 };
+        assert_eq!(composite(src, dst).a, 255);
+    }
+
+    #[test]
+    fn opaque_src_fully_replaces_dst() {
+        let src = Rgb { r: 10, g: 20, b: 30, a: 255 };
+        let dst = Rgb { r: 200, g: 200, b: 200, a: 255 };
+        assert_eq!(composite(src, dst), src);
+    }
+
+    #[test]
+    fn transparent_src_leaves_dst_unchanged() {
+        let src = Rgb { r: 0, g: 0, b: 0, a: 0 };
+        let dst = Rgb { r: 50, g: 60, b: 70, a: 200 };
+        assert_eq!(composite(src, dst), dst);
+    }
+
+    #[test]
+    fn half_alpha_over_transparent_yields_half_alpha() {
+        let src = Rgb { r: 10, g: 20, b: 30, a: 128 };
+        let dst = Rgb { r: 0, g: 0, b: 0, a: 0 };
+        assert_eq!(composite(src, dst).a, 128);
+    }
 }
 
 #[wasm_bindgen]
@@ -34,6 +113,7 @@ impl Image {
             r: 200,
             g: 200,
             b: 255,
+            a: 255,
         }));
         Image {
             width,
@@ -42,10 +122,12 @@ impl Image {
         }
     }
 
+    /// Four bytes per pixel (RGBA), ready for direct upload to a canvas
+    /// `ImageData`.
     pub fn cells(&self) -> Vec<u8> {
         self.cells
             .iter()
-            .map(|&rgb| vec![rgb.r, rgb.g, rgb.b])
+            .map(|&rgb| vec![rgb.r, rgb.g, rgb.b, rgb.a])
             .collect::<Vec<Vec<u8>>>()
             .concat()
     }
@@ -60,22 +142,329 @@ impl Image {
 
     pub fn brush(&self, x: usize, y: usize, color: Vec<u8>) -> Option<Image> {
         let index = y * self.width + x;
-        let color = Rgb {
+        let src = Rgb {
             r: color[0],
             g: color[1],
             b: color[2],
+            a: color[3],
         };
-        if self.cells[index] == color {
+        let dst = self.cells[index];
+        let composited = composite(src, dst);
+        if composited == dst {
             None
         } else {
-            let new_cells = self.cells.update(index, color);
-            Some(Image {
-                width: self.width,
-                height: self.height,
-                cells: new_cells,
-            })
+            Some(self.poke(index, composited))
         }
     }
+
+    /// Encodes this image as an in-memory PNG, suitable for offering the
+    /// user a download of the canvas.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut buffer = image::ImageBuffer::new(self.width as u32, self.height as u32);
+        for (pixel, &rgb) in buffer.pixels_mut().zip(self.cells.iter()) {
+            *pixel = image::Rgba([rgb.r, rgb.g, rgb.b, rgb.a]);
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        buffer
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .expect("encoding an in-memory PNG should never fail");
+        bytes
+    }
+
+    /// Decodes `bytes` as a PNG and builds an `Image` from it, so a real
+    /// picture can be loaded into the editor. Returns `None` if `bytes`
+    /// isn't a valid PNG.
+    pub fn from_png(bytes: Vec<u8>) -> Option<Image> {
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).ok()?;
+        let rgba_image = decoded.to_rgba8();
+        let (width, height) = rgba_image.dimensions();
+        let cells = Vector::from_iter(
+            rgba_image
+                .pixels()
+                .map(|p| Rgb { r: p[0], g: p[1], b: p[2], a: p[3] }),
+        );
+        Some(Image {
+            width: width as usize,
+            height: height as usize,
+            cells,
+        })
+    }
+
+    /// Bucket-fills the contiguous region of cells matching the color at
+    /// `(x, y)` with `color`, using an iterative scanline flood fill so deep
+    /// regions don't blow the stack. Returns `None` if the region is already
+    /// `color`.
+    pub fn fill(&self, x: usize, y: usize, color: Vec<u8>) -> Option<Image> {
+        let target = self.cells[y * self.width + x];
+        let color = Rgb {
+            r: color[0],
+            g: color[1],
+            b: color[2],
+            a: color[3],
+        };
+        if target == color {
+            return None;
+        }
+        let mut cells = self.cells.clone();
+        let mut stack = vec![(x, y)];
+        while let Some((sx, sy)) = stack.pop() {
+            if cells[sy * self.width + sx] != target {
+                continue;
+            }
+            let mut left = sx;
+            while left > 0 && cells[sy * self.width + left - 1] == target {
+                left -= 1;
+            }
+            let mut right = sx;
+            while right + 1 < self.width && cells[sy * self.width + right + 1] == target {
+                right += 1;
+            }
+            for cx in left..=right {
+                cells = cells.update(sy * self.width + cx, color);
+                if sy > 0 && cells[(sy - 1) * self.width + cx] == target {
+                    stack.push((cx, sy - 1));
+                }
+                if sy + 1 < self.height && cells[(sy + 1) * self.width + cx] == target {
+                    stack.push((cx, sy + 1));
+                }
+            }
+        }
+        Some(Image {
+            width: self.width,
+            height: self.height,
+            cells,
+        })
+    }
+}
+
+#[cfg(test)]
+mod fill_tests {
+    use super::*;
+
+    fn white(color: u8) -> Vec<u8> {
+        vec![color, color, color, 255]
+    }
+
+    #[test]
+    fn fills_only_the_contiguous_matching_region() {
+        // A 3x3 image, background color everywhere except a wall down the
+        // middle column separating the left and right thirds.
+        let mut image = Image::new(3, 3);
+        for y in 0..3 {
+            image = image.poke(y * 3 + 1, Rgb { r: 0, g: 0, b: 0, a: 255 });
+        }
+        let filled = image.fill(0, 0, white(1)).unwrap();
+        assert_eq!(filled.cells[0].r, 1);
+        assert_eq!(filled.cells[2 * 3].r, 1);
+        // The wall itself and the region on its far side are untouched.
+        assert_eq!(filled.cells[1].r, 0);
+        assert_eq!(filled.cells[2].r, 200);
+    }
+
+    #[test]
+    fn returns_none_when_target_already_matches_fill_color() {
+        let image = Image::new(2, 2);
+        assert!(image.fill(0, 0, vec![200, 200, 255, 255]).is_none());
+    }
+
+    #[test]
+    fn fill_does_not_cross_image_boundaries() {
+        let image = Image::new(2, 1);
+        let filled = image.fill(0, 0, white(5)).unwrap();
+        assert_eq!(filled.cells[0].r, 5);
+        assert_eq!(filled.cells[1].r, 5);
+    }
+}
+
+impl Image {
+    /// Returns a copy of this image with the cell at `index` set to `color`,
+    /// unconditionally. Used internally wherever a new `Image` needs to be
+    /// derived without the no-op short-circuit `brush` applies (e.g. replaying
+    /// the edit log during recovery).
+    pub(crate) fn poke(&self, index: usize, color: Rgb) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            cells: self.cells.update(index, color),
+        }
+    }
+
+    /// A fully transparent image of the given dimensions, used as the base a
+    /// layer stack composites onto.
+    pub(crate) fn transparent(width: usize, height: usize) -> Image {
+        let cells = Vector::from_iter((0..width * height).map(|_| Rgb {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        }));
+        Image {
+            width,
+            height,
+            cells,
+        }
+    }
+}
+
+/// One layer of a `LayerStack`: its pixels, whether it's included in the
+/// composited result, and how strongly.
+#[derive(Clone)]
+struct Layer {
+    image: Image,
+    visible: bool,
+    opacity: u8,
+}
+
+impl Layer {
+    fn new(image: Image) -> Layer {
+        Layer {
+            image,
+            visible: true,
+            opacity: 255,
+        }
+    }
+}
+
+/// Composites `layer` over `base` using source-over, scaling the layer's own
+/// per-pixel alpha by its opacity first.
+fn blend_layer(base: &Image, layer: &Layer) -> Image {
+    let mut cells = base.cells.clone();
+    for (i, &src) in layer.image.cells.iter().enumerate() {
+        let scaled = Rgb {
+            a: ((src.a as u32 * layer.opacity as u32) / 255) as u8,
+            ..src
+        };
+        cells = cells.update(i, composite(scaled, cells[i]));
+    }
+    Image {
+        width: base.width,
+        height: base.height,
+        cells,
+    }
+}
+
+/// An ordered stack of layers, bottom to top, that a user paints on one at a
+/// time and that flattens into a single `Image` for display. Stored whole as
+/// an `UndoQueue` entry, so `im::Vector`'s structural sharing keeps snapshots
+/// of unchanged layers cheap.
+#[derive(Clone)]
+struct LayerStack {
+    layers: Vec<Layer>,
+}
+
+impl LayerStack {
+    fn new(image: Image) -> LayerStack {
+        LayerStack {
+            layers: vec![Layer::new(image)],
+        }
+    }
+
+    fn layer_image(&self, index: usize) -> Image {
+        self.layers[index].image.clone()
+    }
+
+    fn with_layer_image(&self, index: usize, image: Image) -> LayerStack {
+        let mut stack = self.clone();
+        stack.layers[index].image = image;
+        stack
+    }
+
+    /// Adds a new, fully transparent layer on top of the stack.
+    fn add_layer(&self) -> LayerStack {
+        let mut stack = self.clone();
+        let base = &stack.layers[0].image;
+        let blank = Image::transparent(base.width, base.height);
+        stack.layers.push(Layer::new(blank));
+        stack
+    }
+
+    /// Removes the layer at `index`, unless it's the only layer left.
+    fn remove_layer(&self, index: usize) -> LayerStack {
+        let mut stack = self.clone();
+        if stack.layers.len() > 1 && index < stack.layers.len() {
+            stack.layers.remove(index);
+        }
+        stack
+    }
+
+    fn set_opacity(&self, index: usize, opacity: u8) -> LayerStack {
+        let mut stack = self.clone();
+        if let Some(layer) = stack.layers.get_mut(index) {
+            layer.opacity = opacity;
+        }
+        stack
+    }
+
+    fn toggle_visible(&self, index: usize) -> LayerStack {
+        let mut stack = self.clone();
+        if let Some(layer) = stack.layers.get_mut(index) {
+            layer.visible = !layer.visible;
+        }
+        stack
+    }
+
+    /// Blends every visible layer, bottom to top, onto a transparent base.
+    fn composite(&self) -> Image {
+        let base = &self.layers[0].image;
+        let mut result = Image::transparent(base.width, base.height);
+        for layer in &self.layers {
+            if layer.visible {
+                result = blend_layer(&result, layer);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod layer_stack_tests {
+    use super::*;
+
+    fn solid(width: usize, height: usize, color: Rgb) -> Image {
+        let cells = Vector::from_iter((0..width * height).map(|_| color));
+        Image {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    #[test]
+    fn half_opacity_layer_halves_its_alpha_over_transparent() {
+        let opaque = solid(2, 2, Rgb { r: 10, g: 20, b: 30, a: 255 });
+        let stack = LayerStack::new(opaque).set_opacity(0, 128);
+        let result = stack.composite();
+        assert_eq!(result.cells[0].a, 128);
+    }
+
+    #[test]
+    fn invisible_layer_is_skipped_by_composite() {
+        let stack = LayerStack::new(solid(1, 1, Rgb { r: 1, g: 2, b: 3, a: 255 })).toggle_visible(0);
+        let result = stack.composite();
+        assert_eq!(result.cells[0].a, 0);
+    }
+
+    #[test]
+    fn add_layer_is_transparent_and_becomes_topmost() {
+        let stack = LayerStack::new(solid(1, 1, Rgb { r: 0, g: 0, b: 0, a: 255 })).add_layer();
+        assert_eq!(stack.layers.len(), 2);
+        assert_eq!(stack.layers[1].image.cells[0].a, 0);
+    }
+
+    #[test]
+    fn remove_layer_leaves_at_least_one() {
+        let stack = LayerStack::new(Image::new(1, 1));
+        let stack = stack.remove_layer(0);
+        assert_eq!(stack.layers.len(), 1);
+    }
+
+    #[test]
+    fn top_layer_composites_over_bottom_layer() {
+        let bottom = solid(1, 1, Rgb { r: 0, g: 0, b: 0, a: 255 });
+        let top = solid(1, 1, Rgb { r: 255, g: 255, b: 255, a: 255 });
+        let stack = LayerStack::new(bottom).add_layer().with_layer_image(1, top);
+        assert_eq!(stack.composite().cells[0], Rgb { r: 255, g: 255, b: 255, a: 255 });
+    }
 }
 
 enum Mode {
@@ -146,8 +535,17 @@ impl<T: Clone> UndoQueue<T> {
 }
 
 #[wasm_bindgen]
-struct InternalState {
-    undo_queue: UndoQueue<Image>,
+pub struct InternalState {
+    undo_queue: UndoQueue<LayerStack>,
+    // Which layer `brush`/`fill` target. Purely a UI selection, so it lives
+    // outside the undo-tracked `LayerStack` rather than as an undo step.
+    active_layer: usize,
+    // `None` until `enable_durable_log` is called, so sessions that don't
+    // care about crash recovery don't pay for it. The log only ever tracks
+    // edits to the layer it was opened against (`log_layer`); edits to any
+    // other layer are not durable.
+    log: Option<EditLog>,
+    log_layer: Option<usize>,
 }
 
 #[wasm_bindgen]
@@ -155,12 +553,145 @@ impl InternalState {
     #[wasm_bindgen(constructor)]
     pub fn new(width: usize, height: usize) -> InternalState {
         InternalState {
-            undo_queue: UndoQueue::new(Image::new(width, height)),
+            undo_queue: UndoQueue::new(LayerStack::new(Image::new(width, height))),
+            active_layer: 0,
+            log: None,
+            log_layer: None,
+        }
+    }
+
+    /// Starts persisting every future committed edit to the active layer to
+    /// an append-only log, seeded with that layer's current image as its
+    /// base snapshot. Only edits to this same layer are recorded; switching
+    /// to another layer and painting there is simply not durable.
+    pub fn enable_durable_log(&mut self) {
+        if self.log.is_none() {
+            let image = self.undo_queue.current().layer_image(self.active_layer);
+            self.log = Some(EditLog::new(image));
+            self.log_layer = Some(self.active_layer);
         }
     }
 
+    /// Appends `payloads` to the durable log and checkpoints it if due, but
+    /// only if the log was opened against the layer currently active —
+    /// otherwise the payloads would be replayed on top of the wrong layer's
+    /// snapshot during recovery.
+    fn record_log_edits(&mut self, payloads: Vec<EditPayload>, checkpoint_image: Image) {
+        if self.log_layer != Some(self.active_layer) {
+            return;
+        }
+        if let Some(log) = &mut self.log {
+            log.grow(payloads);
+            if log.needs_checkpoint() {
+                log.checkpoint(checkpoint_image);
+            }
+        }
+    }
+
+    /// Rebuilds the image of the layer the log was opened against from the
+    /// durable log's last checkpoint plus its tail of valid records, and
+    /// resets the undo queue to that recovered state. A no-op if durable
+    /// logging was never enabled.
+    pub fn recover_from_log(&mut self) {
+        if let (Some(log), Some(layer)) = (&self.log, self.log_layer) {
+            let image = log.recover(|image, payload, _offset| image.poke(payload.index, payload.new));
+            let stack = self.undo_queue.current().with_layer_image(layer, image);
+            self.undo_queue = UndoQueue::new(stack);
+        }
+    }
+
+    /// Serializes the durable log to bytes, for the caller to persist
+    /// somewhere that actually survives the WASM instance going away (e.g.
+    /// `localStorage`/`IndexedDB`), unlike `recover_from_log` which only
+    /// ever helps within this same still-alive `InternalState`. Returns
+    /// `None` if durable logging was never enabled.
+    pub fn log_bytes(&self) -> Option<Vec<u8>> {
+        self.log.as_ref().map(EditLog::to_bytes)
+    }
+
+    /// Restores the durable log from bytes previously returned by
+    /// `log_bytes`, replaying it onto `layer`, and resets the undo queue to
+    /// the recovered state — the counterpart that makes a session actually
+    /// recoverable after a real browser crash or reload. Returns `false` if
+    /// `bytes` couldn't be parsed as a log at all.
+    pub fn load_log_bytes(&mut self, bytes: Vec<u8>, layer: usize) -> bool {
+        let log = match EditLog::from_bytes(&bytes) {
+            Some(log) => log,
+            None => return false,
+        };
+        let image = log.recover(|image, payload, _offset| image.poke(payload.index, payload.new));
+        let stack = self.undo_queue.current().with_layer_image(layer, image);
+        self.undo_queue = UndoQueue::new(stack);
+        self.active_layer = layer;
+        self.log_layer = Some(layer);
+        self.log = Some(log);
+        true
+    }
+
+    /// The flattened, visible result of compositing every layer, ready for
+    /// display.
     pub fn image(&self) -> Image {
-        self.undo_queue.current()
+        self.composite()
+    }
+
+    /// Blends every visible layer, bottom to top, into a single `Image`.
+    pub fn composite(&self) -> Image {
+        self.undo_queue.current().composite()
+    }
+
+    /// Decodes `bytes` as a PNG and loads it into the active layer as a new
+    /// undo entry, so loading a picture is undoable like any other edit.
+    /// Returns `true` on success, `false` if `bytes` wasn't a valid PNG.
+    pub fn load_png(&mut self, bytes: Vec<u8>) -> bool {
+        match Image::from_png(bytes) {
+            None => false,
+            Some(image) => {
+                let stack = self.undo_queue.current().with_layer_image(self.active_layer, image);
+                self.undo_queue.push(stack);
+                true
+            }
+        }
+    }
+
+    /// Adds a new, fully transparent layer on top of the stack and makes it
+    /// the active one.
+    pub fn add_layer(&mut self) {
+        let stack = self.undo_queue.current().add_layer();
+        self.active_layer = stack.layers.len() - 1;
+        self.undo_queue.push(stack);
+    }
+
+    /// Removes the layer at `index`, unless it's the only one left. If
+    /// `index` is below the active layer, the active layer shifts down to
+    /// stay pointed at the same layer; if `index` is the active layer
+    /// itself, the layer that slides into its slot becomes active.
+    pub fn remove_layer(&mut self, index: usize) {
+        let before = self.undo_queue.current().layers.len();
+        let stack = self.undo_queue.current().remove_layer(index);
+        if stack.layers.len() < before && index < self.active_layer {
+            self.active_layer -= 1;
+        }
+        if self.active_layer >= stack.layers.len() {
+            self.active_layer = stack.layers.len() - 1;
+        }
+        self.undo_queue.push(stack);
+    }
+
+    /// Switches which layer `brush`/`fill` target. Not an undo step.
+    pub fn set_active_layer(&mut self, index: usize) {
+        if index < self.undo_queue.current().layers.len() {
+            self.active_layer = index;
+        }
+    }
+
+    pub fn set_opacity(&mut self, index: usize, opacity: u8) {
+        let stack = self.undo_queue.current().set_opacity(index, opacity);
+        self.undo_queue.push(stack);
+    }
+
+    pub fn toggle_visible(&mut self, index: usize) {
+        let stack = self.undo_queue.current().toggle_visible(index);
+        self.undo_queue.push(stack);
     }
 
     pub fn undo(&mut self) {
@@ -179,14 +710,118 @@ impl InternalState {
         self.undo_queue.close_undo_block();
     }
 
+    /// Bucket-fills the region at `(x, y)` with `color`, collapsing the
+    /// whole fill into a single undo step regardless of how many cells it
+    /// touches.
+    pub fn fill(&mut self, x: usize, y: usize, color: Vec<u8>) {
+        let stack = self.undo_queue.current();
+        let image = stack.layer_image(self.active_layer);
+        self.undo_queue.start_undo_block();
+        if let Some(new_image) = image.fill(x, y, color) {
+            self.record_log_edits(diff_payloads(&image, &new_image), new_image.clone());
+            self.undo_queue
+                .push(stack.with_layer_image(self.active_layer, new_image));
+        }
+        self.undo_queue.close_undo_block();
+    }
+
+    /// Rasterizes a line from `(x0, y0)` to `(x1, y1)` with Bresenham's
+    /// algorithm and brushes every point along it, so a fast mouse drag
+    /// paints a continuous stroke instead of isolated dots. The whole line
+    /// collapses into a single undo step.
+    pub fn brush_line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: Vec<u8>) {
+        let mut x0 = x0 as isize;
+        let mut y0 = y0 as isize;
+        let x1 = x1 as isize;
+        let y1 = y1 as isize;
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        self.undo_queue.start_undo_block();
+        loop {
+            self.brush(x0 as usize, y0 as usize, color.clone());
+            if x0 == x1 && y0 == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x0 += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y0 += sy;
+            }
+        }
+        self.undo_queue.close_undo_block();
+    }
+
     pub fn brush(&mut self, x: usize, y: usize, color: Vec<u8>) {
-        let image = self.undo_queue.current();
+        let stack = self.undo_queue.current();
+        let image = stack.layer_image(self.active_layer);
+        let index = y * image.width + x;
+        let old = image.cells[index];
         let optional_image = image.brush(x, y, color);
         match optional_image {
             None => (),
             Some(new_image) => {
-                self.undo_queue.push(new_image);
+                let new = new_image.cells[index];
+                self.record_log_edits(vec![EditPayload { index, old, new }], new_image.clone());
+                self.undo_queue
+                    .push(stack.with_layer_image(self.active_layer, new_image));
             }
         }
     }
 }
+
+#[cfg(test)]
+mod brush_line_tests {
+    use super::*;
+
+    fn black() -> Vec<u8> {
+        vec![0, 0, 0, 255]
+    }
+
+    #[test]
+    fn paints_every_cell_along_a_diagonal_line() {
+        let mut state = InternalState::new(4, 4);
+        state.brush_line(0, 0, 3, 3, black());
+        let image = state.image();
+        for i in 0..4 {
+            assert_eq!(image.cells[i * 4 + i].r, 0);
+        }
+    }
+
+    #[test]
+    fn paints_a_horizontal_line() {
+        let mut state = InternalState::new(4, 1);
+        state.brush_line(0, 0, 3, 0, black());
+        let image = state.image();
+        for i in 0..4 {
+            assert_eq!(image.cells[i].r, 0);
+        }
+    }
+
+    #[test]
+    fn paints_a_vertical_line() {
+        let mut state = InternalState::new(1, 4);
+        state.brush_line(0, 0, 0, 3, black());
+        let image = state.image();
+        for i in 0..4 {
+            assert_eq!(image.cells[i].r, 0);
+        }
+    }
+
+    #[test]
+    fn collapses_the_whole_stroke_into_a_single_undo_step() {
+        let mut state = InternalState::new(4, 4);
+        state.brush_line(0, 0, 3, 3, black());
+        assert_eq!(state.undo_queue.queue.len(), 2);
+        state.undo();
+        let image = state.image();
+        assert_eq!(image.cells[5].r, 200);
+    }
+}
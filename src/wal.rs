@@ -0,0 +1,307 @@
+use crate::{Image, Rgb};
+use im::Vector;
+use std::convert::TryInto;
+use std::iter::FromIterator;
+
+/// Number of records held in a single log block before a new one is
+/// allocated. Keeping blocks fixed-size means recovery can stop mid-block
+/// without needing to know anything about neighbouring blocks.
+const BLOCK_RECORDS: usize = 64;
+
+/// Number of committed records between automatic snapshot checkpoints. Once
+/// this many records have been appended since the last checkpoint, the log
+/// folds them into a fresh `Image` snapshot and starts over, so the log
+/// never has to be replayed further back than one checkpoint's worth.
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// A single committed edit: the cell that changed, and its old and new
+/// color. This is the unit of work the write-ahead log persists.
+#[derive(Clone, Copy)]
+pub(crate) struct EditPayload {
+    pub(crate) index: usize,
+    pub(crate) old: Rgb,
+    pub(crate) new: Rgb,
+}
+
+impl EditPayload {
+    /// A cheap rolling checksum over the payload's fields, used to detect a
+    /// record that was torn off mid-write (e.g. by a crash during append).
+    fn checksum(&self) -> u32 {
+        let mut sum = self.index as u32;
+        for byte in [
+            self.old.r, self.old.g, self.old.b, self.old.a, self.new.r, self.new.g, self.new.b,
+            self.new.a,
+        ] {
+            sum = sum.wrapping_mul(31).wrapping_add(byte as u32);
+        }
+        sum
+    }
+}
+
+/// A record as it sits in the log: the payload plus the checksum it was
+/// written with.
+#[derive(Clone, Copy)]
+struct Record {
+    payload: EditPayload,
+    checksum: u32,
+}
+
+/// A fixed-size block of records. The log is a sequence of these, appended
+/// in order; a block is sealed once it reaches `BLOCK_RECORDS` and a new one
+/// is started.
+struct Block {
+    records: Vec<Record>,
+}
+
+impl Block {
+    fn new() -> Block {
+        Block {
+            records: Vec::with_capacity(BLOCK_RECORDS),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.records.len() >= BLOCK_RECORDS
+    }
+}
+
+/// An append-only, crash-recoverable log of committed edits, with periodic
+/// full-image checkpoints so the log can be truncated rather than growing
+/// without bound.
+pub(crate) struct EditLog {
+    blocks: Vec<Block>,
+    snapshot: Image,
+    records_since_checkpoint: usize,
+}
+
+impl EditLog {
+    /// Starts a new log with `snapshot` as the base image recovery replays
+    /// on top of.
+    pub(crate) fn new(snapshot: Image) -> EditLog {
+        EditLog {
+            blocks: vec![Block::new()],
+            snapshot,
+            records_since_checkpoint: 0,
+        }
+    }
+
+    /// Appends `records` to the tail of the log, rolling over to a new block
+    /// whenever the current one fills up. Returns how many records were
+    /// appended.
+    pub(crate) fn grow(&mut self, records: Vec<EditPayload>) -> usize {
+        let mut appended = 0;
+        for payload in records {
+            if self.blocks.last().unwrap().is_full() {
+                self.blocks.push(Block::new());
+            }
+            let block = self.blocks.last_mut().unwrap();
+            block.records.push(Record {
+                payload,
+                checksum: payload.checksum(),
+            });
+            appended += 1;
+            self.records_since_checkpoint += 1;
+        }
+        appended
+    }
+
+    /// Whether enough records have accumulated since the last checkpoint
+    /// that the caller should fold them into a fresh snapshot.
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.records_since_checkpoint >= CHECKPOINT_INTERVAL
+    }
+
+    /// Makes `image` the new base snapshot and discards the log blocks, so a
+    /// future recovery only has to replay the tail written after this point.
+    /// The discarded records are not applied to `image` here.
+    pub(crate) fn checkpoint(&mut self, image: Image) {
+        self.snapshot = image;
+        self.blocks = vec![Block::new()];
+        self.records_since_checkpoint = 0;
+    }
+
+    /// Returns every record from the last checkpoint up to (but not
+    /// including) the first one that fails its checksum. A checksum
+    /// mismatch means the record was torn off mid-write by a crash; rather
+    /// than failing the whole load, recovery just stops there and accepts
+    /// everything written before it.
+    fn valid_records(&self) -> Vec<(EditPayload, usize)> {
+        let mut valid = Vec::new();
+        let mut offset = 0;
+        'blocks: for block in &self.blocks {
+            for record in &block.records {
+                if record.checksum != record.payload.checksum() {
+                    break 'blocks;
+                }
+                valid.push((record.payload, offset));
+                offset += 1;
+            }
+        }
+        valid
+    }
+
+    /// Rebuilds an `Image` by starting from the checkpointed snapshot and
+    /// replaying every valid record in order, handing each `(payload,
+    /// offset)` pair to `apply_payload` to fold into the running image.
+    pub(crate) fn recover<F>(&self, mut apply_payload: F) -> Image
+    where
+        F: FnMut(Image, EditPayload, usize) -> Image,
+    {
+        let mut image = self.snapshot.clone();
+        for (payload, offset) in self.valid_records() {
+            image = apply_payload(image, payload, offset);
+        }
+        image
+    }
+
+    /// Serializes the log to a flat byte buffer that can be persisted
+    /// outside the WASM heap (e.g. in `localStorage` or `IndexedDB`) and fed
+    /// back into `from_bytes` to recover a session after a real browser
+    /// crash or reload, which an in-heap `EditLog` alone cannot survive.
+    /// Layout: `width:u32 LE`, `height:u32 LE`, the snapshot's raw RGBA
+    /// cells, then one 16-byte record per committed edit (`index:u32 LE`,
+    /// `old` RGBA, `new` RGBA, `checksum:u32 LE`).
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.snapshot.width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.snapshot.height as u32).to_le_bytes());
+        for &rgb in self.snapshot.cells.iter() {
+            bytes.extend_from_slice(&[rgb.r, rgb.g, rgb.b, rgb.a]);
+        }
+        for block in &self.blocks {
+            for record in &block.records {
+                let payload = &record.payload;
+                bytes.extend_from_slice(&(payload.index as u32).to_le_bytes());
+                bytes.extend_from_slice(&[payload.old.r, payload.old.g, payload.old.b, payload.old.a]);
+                bytes.extend_from_slice(&[payload.new.r, payload.new.g, payload.new.b, payload.new.a]);
+                bytes.extend_from_slice(&record.checksum.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Rebuilds a log from bytes written by `to_bytes`. Stops reading
+    /// records at the first one that is truncated or fails its checksum —
+    /// the same torn-record tolerance `valid_records` applies to an in-heap
+    /// log — and accepts everything read before that. Returns `None` if
+    /// even the snapshot header and cells can't be read.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<EditLog> {
+        let width = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+        let height = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?) as usize;
+        let cells_end = 8usize.checked_add(width.checked_mul(height)?.checked_mul(4)?)?;
+        let cells = Vector::from_iter(
+            bytes
+                .get(8..cells_end)?
+                .chunks_exact(4)
+                .map(|c| Rgb { r: c[0], g: c[1], b: c[2], a: c[3] }),
+        );
+        let mut log = EditLog::new(Image { width, height, cells });
+
+        let mut offset = cells_end;
+        while let Some(chunk) = bytes.get(offset..offset + 16) {
+            let index = u32::from_le_bytes(chunk[0..4].try_into().unwrap()) as usize;
+            let old = Rgb { r: chunk[4], g: chunk[5], b: chunk[6], a: chunk[7] };
+            let new = Rgb { r: chunk[8], g: chunk[9], b: chunk[10], a: chunk[11] };
+            let checksum = u32::from_le_bytes(chunk[12..16].try_into().unwrap());
+            let payload = EditPayload { index, old, new };
+            if payload.checksum() != checksum {
+                break;
+            }
+            if log.blocks.last().unwrap().is_full() {
+                log.blocks.push(Block::new());
+            }
+            log.blocks.last_mut().unwrap().records.push(Record { payload, checksum });
+            log.records_since_checkpoint += 1;
+            offset += 16;
+        }
+        Some(log)
+    }
+}
+
+#[cfg(test)]
+mod edit_log_tests {
+    use super::*;
+
+    fn payload(index: usize, old: u8, new: u8) -> EditPayload {
+        EditPayload {
+            index,
+            old: Rgb { r: old, g: old, b: old, a: 255 },
+            new: Rgb { r: new, g: new, b: new, a: 255 },
+        }
+    }
+
+    #[test]
+    fn recover_replays_appended_records_onto_snapshot() {
+        let mut log = EditLog::new(Image::new(2, 2));
+        log.grow(vec![payload(0, 200, 10), payload(3, 200, 20)]);
+        let image = log.recover(|image, p, _offset| image.poke(p.index, p.new));
+        assert_eq!(image.cells[0].r, 10);
+        assert_eq!(image.cells[3].r, 20);
+    }
+
+    #[test]
+    fn checkpoint_folds_records_and_resets_counter() {
+        let mut log = EditLog::new(Image::new(2, 2));
+        log.grow(vec![payload(0, 200, 10)]);
+        let folded = log.recover(|image, p, _offset| image.poke(p.index, p.new));
+        log.checkpoint(folded);
+        assert!(!log.needs_checkpoint());
+        assert_eq!(log.blocks.len(), 1);
+        assert!(log.blocks[0].records.is_empty());
+        assert_eq!(log.snapshot.cells[0].r, 10);
+    }
+
+    #[test]
+    fn needs_checkpoint_once_interval_reached() {
+        let mut log = EditLog::new(Image::new(1, 1));
+        for _ in 0..CHECKPOINT_INTERVAL {
+            log.grow(vec![payload(0, 200, 10)]);
+        }
+        assert!(log.needs_checkpoint());
+    }
+
+    #[test]
+    fn grow_rolls_over_to_a_new_block_once_one_fills() {
+        let mut log = EditLog::new(Image::new(1, 1));
+        log.grow((0..BLOCK_RECORDS + 1).map(|i| payload(0, 0, i as u8)).collect());
+        assert_eq!(log.blocks.len(), 2);
+        assert_eq!(log.blocks[0].records.len(), BLOCK_RECORDS);
+        assert_eq!(log.blocks[1].records.len(), 1);
+    }
+
+    #[test]
+    fn valid_records_stops_at_a_torn_record() {
+        let mut log = EditLog::new(Image::new(1, 1));
+        log.grow(vec![payload(0, 200, 10), payload(0, 10, 30)]);
+        log.blocks[0].records[1].checksum = 0xdead_beef;
+        assert_eq!(log.valid_records().len(), 1);
+    }
+
+    #[test]
+    fn to_bytes_from_bytes_round_trips() {
+        let mut log = EditLog::new(Image::new(2, 2));
+        log.grow(vec![payload(0, 200, 10), payload(3, 200, 20)]);
+        let bytes = log.to_bytes();
+        let restored = EditLog::from_bytes(&bytes).expect("round trip should parse");
+        let image = restored.recover(|image, p, _offset| image.poke(p.index, p.new));
+        assert_eq!(image.cells[0].r, 10);
+        assert_eq!(image.cells[3].r, 20);
+    }
+
+    #[test]
+    fn from_bytes_truncated_mid_record_keeps_records_written_before_it() {
+        let mut log = EditLog::new(Image::new(2, 2));
+        log.grow(vec![payload(0, 200, 10), payload(3, 200, 20)]);
+        let mut bytes = log.to_bytes();
+        bytes.truncate(bytes.len() - 10);
+        let restored = EditLog::from_bytes(&bytes).expect("snapshot header should still parse");
+        let image = restored.recover(|image, p, _offset| image.poke(p.index, p.new));
+        assert_eq!(image.cells[0].r, 10);
+        assert_eq!(image.cells[3].r, 200);
+    }
+
+    #[test]
+    fn from_bytes_rejects_a_buffer_too_short_for_the_snapshot() {
+        assert!(EditLog::from_bytes(&[1, 0, 0, 0]).is_none());
+    }
+}